@@ -1,5 +1,51 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
 use std::str;
 
+// Maps an input byte to its index in 0..SIGMA, the set of distinct symbols
+// the tree needs to be able to tell apart. This is what lets the densest
+// child layout (see `ChildLayout::NodeFull` below) be sized to the actual
+// alphabet instead of always paying for all 256 byte values.
+//
+// BIJECTIVE must be true only if `index` is injective over all 256 byte
+// values, i.e. distinct bytes always map to distinct indices. `NodeFull`
+// stores one ref per index rather than per byte, so a non-injective mapping
+// would let two distinct children silently collide into the same slot;
+// `ChildLayout::promote` refuses to build a `NodeFull` for alphabets that
+// can't make this guarantee (see there).
+trait Alphabet<const SIGMA: usize> {
+    const BIJECTIVE: bool;
+    fn index(byte: u8) -> usize;
+}
+
+// The general byte-string case: every value is its own index.
+struct Identity;
+impl Alphabet<256> for Identity {
+    const BIJECTIVE: bool = true;
+    fn index(byte: u8) -> usize {
+        byte as usize
+    }
+}
+
+// DNA sequences only ever contain A/C/G/T plus a terminator, so a node's
+// widest possible child layout shrinks from 256 slots to 5. The mapping
+// collapses every non-ACGT byte to the same index, so it is not bijective.
+struct DnaAlphabet;
+impl Alphabet<5> for DnaAlphabet {
+    const BIJECTIVE: bool = false;
+    fn index(byte: u8) -> usize {
+        match byte {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => 4, // terminator, or anything else
+        }
+    }
+}
+
 // Store positions in packed (u32) form; this limits us to under 4GB of
 // payload but makes the data structures a bit more compact.
 struct PackedPos(u32);
@@ -24,6 +70,14 @@ enum NodeRef {
     Inner(usize),
 }
 
+// Where a pattern walk from the root ends up: either it runs out exactly at
+// a leaf (a single matching suffix), or it stops within or at the end of an
+// edge leading to an internal node (every leaf in that subtree matches).
+enum MatchPoint {
+    Leaf(usize),           // suffix start position
+    Subtree(usize, usize), // (inner node index, string-depth of that node)
+}
+
 // Node references are also packed into u32s, which further limits us
 // to about 2GB of payload and the equivalent number of internal nodes,
 // but lets us pack the data more tightly.
@@ -32,6 +86,7 @@ struct PackedRef(u32);
 
 impl PackedRef {
     const MAX_IND : usize = (std::u32::MAX / 2) as usize;
+    const NONE: PackedRef = PackedRef(0);
 
     fn from_inner(ind: usize) -> Self {
         assert!(ind <= Self::MAX_IND);
@@ -55,159 +110,418 @@ impl PackedRef {
     }
 }
 
-// An internal node in the suffix tree. Keeping a full array of 256
-// child node references is a _terrible_ idea for memory use, and completely
-// swamps the benefit of having PackedPos. Making PackedRefs smaller does help
-// since this structure is essentially nothing but. Really though you would use
-// a different representation for child links, classically: a linked list (ugh) or
-// hash table. Another alternative is typical radix tree-style multiple node types
-// depending on child count.
+// Radix tree-style node for the child links. Most internal nodes only have a
+// handful of children, so we keep them in a small linearly-searched array and
+// only grow to wider (and more expensive) layouts as children are added.
+// Node48's index table and NodeFull's refs table are boxed: without that, the
+// enum's size is its largest variant's regardless of which one is active, so
+// every Node4/Node16 would still pay for a 256-byte index and a SIGMA-wide
+// refs array inline, defeating the point of the tiered layout. NodeFull is
+// the fallback for the rare node with many children; unlike the fixed
+// 256-wide array this used to be, it's sized to the alphabet (SIGMA) and
+// keyed by `Alphabet::index` rather than the raw byte, so a small alphabet
+// (e.g. DNA's 5 symbols) doesn't pay for 256 slots either. Node4/16/48 stay
+// keyed by the raw byte, since they're cheap regardless of alphabet size.
+enum ChildLayout<const SIGMA: usize> {
+    Node4 { keys: [u8; 4], refs: [PackedRef; 4], len: u8 },
+    Node16 { keys: [u8; 16], refs: [PackedRef; 16], len: u8 },
+    Node48 { index: Box<[u8; 256]>, refs: Box<[PackedRef; 48]>, len: u8 },
+    NodeFull { refs: Box<[PackedRef; SIGMA]> },
+}
+
+impl<const SIGMA: usize> ChildLayout<SIGMA> {
+    fn empty() -> Self {
+        ChildLayout::Node4 { keys: [0; 4], refs: [PackedRef::NONE; 4], len: 0 }
+    }
+
+    // Every slot maps to the same target; used for the Top node, whose links
+    // all point straight back to the root.
+    fn filled(r: PackedRef) -> Self {
+        ChildLayout::NodeFull { refs: Box::new([r; SIGMA]) }
+    }
+
+    fn get<A: Alphabet<SIGMA>>(&self, ch: u8) -> PackedRef {
+        match self {
+            ChildLayout::Node4 { keys, refs, len } => {
+                (0..*len as usize).find(|&i| keys[i] == ch).map_or(PackedRef::NONE, |i| refs[i])
+            }
+            ChildLayout::Node16 { keys, refs, len } => {
+                (0..*len as usize).find(|&i| keys[i] == ch).map_or(PackedRef::NONE, |i| refs[i])
+            }
+            ChildLayout::Node48 { index, refs, .. } => {
+                match index[ch as usize] {
+                    0 => PackedRef::NONE,
+                    slot => refs[slot as usize - 1],
+                }
+            }
+            ChildLayout::NodeFull { refs } => refs[A::index(ch)],
+        }
+    }
+
+    // Inserts a new child, or updates the ref of an existing one, growing to
+    // the next wider layout first if this one is full.
+    fn set<A: Alphabet<SIGMA>>(&mut self, ch: u8, r: PackedRef) {
+        match self {
+            ChildLayout::Node4 { keys, refs, len } => {
+                if let Some(i) = (0..*len as usize).find(|&i| keys[i] == ch) {
+                    refs[i] = r;
+                } else if (*len as usize) < keys.len() {
+                    keys[*len as usize] = ch;
+                    refs[*len as usize] = r;
+                    *len += 1;
+                } else {
+                    self.promote::<A>();
+                    self.set::<A>(ch, r);
+                }
+            }
+            ChildLayout::Node16 { keys, refs, len } => {
+                if let Some(i) = (0..*len as usize).find(|&i| keys[i] == ch) {
+                    refs[i] = r;
+                } else if (*len as usize) < keys.len() {
+                    keys[*len as usize] = ch;
+                    refs[*len as usize] = r;
+                    *len += 1;
+                } else {
+                    self.promote::<A>();
+                    self.set::<A>(ch, r);
+                }
+            }
+            ChildLayout::Node48 { index, refs, len } => {
+                match index[ch as usize] {
+                    0 if (*len as usize) < refs.len() => {
+                        index[ch as usize] = *len + 1;
+                        refs[*len as usize] = r;
+                        *len += 1;
+                    }
+                    0 => {
+                        self.promote::<A>();
+                        self.set::<A>(ch, r);
+                    }
+                    slot => refs[slot as usize - 1] = r,
+                }
+            }
+            ChildLayout::NodeFull { refs } => refs[A::index(ch)] = r,
+        }
+    }
+
+    // Grows to the next larger layout, carrying over all present children.
+    fn promote<A: Alphabet<SIGMA>>(&mut self) {
+        *self = match self {
+            ChildLayout::Node4 { keys, refs, len } => {
+                let mut bigger = ChildLayout::Node16 { keys: [0; 16], refs: [PackedRef::NONE; 16], len: 0 };
+                for i in 0..*len as usize {
+                    bigger.set::<A>(keys[i], refs[i]);
+                }
+                bigger
+            }
+            ChildLayout::Node16 { keys, refs, len } => {
+                let mut bigger = ChildLayout::Node48 { index: Box::new([0; 256]), refs: Box::new([PackedRef::NONE; 48]), len: 0 };
+                for i in 0..*len as usize {
+                    bigger.set::<A>(keys[i], refs[i]);
+                }
+                bigger
+            }
+            ChildLayout::Node48 { index, refs, .. } => {
+                assert!(A::BIJECTIVE,
+                    "alphabet's index mapping isn't bijective; a NodeFull built from it could \
+                     silently merge distinct children into the same slot");
+                let mut bigger = ChildLayout::NodeFull { refs: Box::new([PackedRef::NONE; SIGMA]) };
+                for ch in 0..256 {
+                    if index[ch] != 0 {
+                        bigger.set::<A>(ch as u8, refs[index[ch] as usize - 1]);
+                    }
+                }
+                bigger
+            }
+            ChildLayout::NodeFull { .. } => unreachable!("NodeFull is already the widest layout"),
+        };
+    }
+
+    fn iter(&self) -> ChildIter<'_, SIGMA> {
+        ChildIter { layout: self, pos: 0 }
+    }
+}
+
+// Iterates the present (key, child ref) pairs of a node. For Node4/16/48 the
+// key is the raw byte; for NodeFull it's the alphabet-mapped index.
+// `ChildLayout::promote` only ever builds a NodeFull for bijective alphabets
+// (see `Alphabet::BIJECTIVE`), where index and byte coincide in practice, but
+// Top's `ChildLayout::filled` layout is a NodeFull under every alphabet, so
+// its keys are indices rather than bytes (see `SuffixTree::save`).
+struct ChildIter<'a, const SIGMA: usize> {
+    layout: &'a ChildLayout<SIGMA>,
+    pos: usize,
+}
+
+impl<'a, const SIGMA: usize> Iterator for ChildIter<'a, SIGMA> {
+    type Item = (u8, PackedRef);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.layout {
+            ChildLayout::Node4 { keys, refs, len } => {
+                if self.pos < *len as usize {
+                    let item = (keys[self.pos], refs[self.pos]);
+                    self.pos += 1;
+                    Some(item)
+                } else {
+                    None
+                }
+            }
+            ChildLayout::Node16 { keys, refs, len } => {
+                if self.pos < *len as usize {
+                    let item = (keys[self.pos], refs[self.pos]);
+                    self.pos += 1;
+                    Some(item)
+                } else {
+                    None
+                }
+            }
+            ChildLayout::Node48 { index, refs, .. } => {
+                while self.pos < 256 {
+                    let ch = self.pos;
+                    self.pos += 1;
+                    if index[ch] != 0 {
+                        return Some((ch as u8, refs[index[ch] as usize - 1]));
+                    }
+                }
+                None
+            }
+            ChildLayout::NodeFull { refs } => {
+                while self.pos < SIGMA {
+                    let ch = self.pos;
+                    self.pos += 1;
+                    if !refs[ch].is_none() {
+                        return Some((ch as u8, refs[ch]));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+// An internal node in the suffix tree. Child links used to be a full array of
+// 256 PackedRefs, which is a _terrible_ idea for memory use and completely
+// swamps the benefit of having PackedPos: most internal nodes only have a
+// couple of children, so ChildLayout grows the backing storage lazily
+// (Node4 -> Node16 -> Node48 -> NodeFull) instead of paying for SIGMA slots
+// everywhere. SIGMA is the size of the alphabet in use (see `Alphabet`).
 //
 // We store the label of the incoming edge from the parent along with nodes, since
 // every Node (save the root, which is special in other ways) has at least one
 // incoming edge, because this is a tree.
-struct Node {
+struct Node<const SIGMA: usize> {
     // The label of the incoming edge is payload[begin..end] (begin inclusive, end exclusive)
     begin: PackedPos,
     end: PackedPos,
     suffix: PackedRef, // suffix link
-    child: [PackedRef; 256], // at most one child per possible character
+    child: ChildLayout<SIGMA>,
 }
 
-impl Node {
+impl<const SIGMA: usize> Node<SIGMA> {
     fn new_special(begin: usize, end: usize, suffix_ind: usize, child_ind: usize) -> Self {
         Node {
             begin: PackedPos::from(begin),
             end: PackedPos::from(end),
             suffix: PackedRef::from_inner(suffix_ind),
-            child: [PackedRef::from_inner(child_ind); 256]
+            child: ChildLayout::filled(PackedRef::from_inner(child_ind)),
         }
     }
 
     fn new(begin: usize, end: usize, suffix_ind: usize) -> Self {
-        Self::new_special(begin, end, suffix_ind, 0)
+        Node {
+            begin: PackedPos::from(begin),
+            end: PackedPos::from(end),
+            suffix: PackedRef::from_inner(suffix_ind),
+            child: ChildLayout::empty(),
+        }
     }
 
     fn label_len(&self) -> usize {
         self.end.unpack() - self.begin.unpack()
     }
+
+    fn get_child<A: Alphabet<SIGMA>>(&self, ch: u8) -> PackedRef {
+        self.child.get::<A>(ch)
+    }
+
+    fn set_child<A: Alphabet<SIGMA>>(&mut self, ch: u8, r: PackedRef) {
+        self.child.set::<A>(ch, r)
+    }
 }
 
+#[derive(Clone, Copy)]
 struct Cursor {
     node: usize,
     pos: usize,
 }
 
-struct SuffixTree<'a> {
-    payload: &'a [u8],
-    nodes: Vec<Node>,
-}
-
-impl<'a> SuffixTree<'a> {
-    fn update(&mut self, cur_in: Cursor, new_end: usize) -> Cursor {
-        let mut cur = cur_in;
-        let new_ch = self.payload[new_end];
-        let mut prev_insert_idx: usize = 0;
-
-        loop {
-            // Canonicalize active point
-            while cur.pos < new_end {
-                let link_ch = self.payload[cur.pos];
-                match self.nodes[cur.node].child[link_ch as usize].unpack() {
-                    NodeRef::Leaf(_) => {
-                        // Leafs can absorb the entire rest of the string, and we can't
-                        // descend into them; nothing to do.
+// One step of Ukkonen's online construction: extends the tree built over
+// `payload[..new_end]` with the character at `new_end`, starting from the
+// active point `cur_in`. Free-standing (rather than a `SuffixTree` method)
+// so both `SuffixTree::update` and `SuffixTreeBuilder::push` can drive it
+// without either owning a whole tree up front.
+fn advance<const SIGMA: usize, A: Alphabet<SIGMA>>(
+    nodes: &mut Vec<Node<SIGMA>>,
+    payload: &[u8],
+    cur_in: Cursor,
+    new_end: usize,
+) -> Cursor {
+    let mut cur = cur_in;
+    let new_ch = payload[new_end];
+    let mut prev_insert_idx: usize = 0;
+
+    loop {
+        // Canonicalize active point
+        while cur.pos < new_end {
+            let link_ch = payload[cur.pos];
+            match nodes[cur.node].get_child::<A>(link_ch).unpack() {
+                NodeRef::Leaf(_) => {
+                    // Leafs can absorb the entire rest of the string, and we can't
+                    // descend into them; nothing to do.
+                    break;
+                }
+                NodeRef::Inner(idx) => {
+                    debug_assert!(idx != 0, "canonicalize should only follow real links");
+                    let len = nodes[idx].label_len();
+                    if len > new_end - cur.pos {
+                        // Label of this inner node extends past the characters
+                        // we currently have, so we're done!
                         break;
                     }
-                    NodeRef::Inner(idx) => {
-                        debug_assert!(idx != 0, "canonicalize should only follow real links");
-                        let len = self.nodes[idx].label_len();
-                        if len > new_end - cur.pos {
-                            // Label of this inner node extends past the characters
-                            // we currently have, so we're done!
-                            break;
-                        }
-
-                        // Descend into this node and keep going
-                        cur.node = idx;
-                        cur.pos += len;
-                    }
+
+                    // Descend into this node and keep going
+                    cur.node = idx;
+                    cur.pos += len;
                 }
             }
+        }
 
-            // Do we have an outgoing link with the new character already?
-            let insert_node_idx = if cur.pos == new_end {
-                // Would insert right below active node; do we have
-                // a link for this character already?
-                if !self.nodes[cur.node].child[new_ch as usize].is_none() {
-                    // We have this already; nothing to do for now!
-                    break;
-                } else {
-                    // Insert right below current node.
-                    cur.node
-                }
+        // Do we have an outgoing link with the new character already?
+        let insert_node_idx = if cur.pos == new_end {
+            // Would insert right below active node; do we have
+            // a link for this character already?
+            if !nodes[cur.node].get_child::<A>(new_ch).is_none() {
+                // We have this already; nothing to do for now! But a split
+                // earlier in this same phase may still be waiting for its
+                // suffix link, which always resolves to the active point of
+                // the extension that follows it, break or not.
+                nodes[prev_insert_idx].suffix = PackedRef::from_inner(cur.node);
+                break;
             } else {
-                // We're in the middle of a longer label; check whether we have a
-                // mismatch (in which case we need to split) or not.
+                // Insert right below current node.
+                cur.node
+            }
+        } else {
+            // We're in the middle of a longer label; check whether we have a
+            // mismatch (in which case we need to split) or not.
 
-                // First character at the active point tells us which edge to
-                // follow from the active node
-                let edge_select_ch = self.payload[cur.pos] as usize;
-                let edge_ref = self.nodes[cur.node].child[edge_select_ch];
+            // First character at the active point tells us which edge to
+            // follow from the active node
+            let edge_select_ch = payload[cur.pos];
+            let edge_ref = nodes[cur.node].get_child::<A>(edge_select_ch);
 
-                // For us to get here, this reference should exist
-                debug_assert!(!edge_ref.is_none());
+            // For us to get here, this reference should exist
+            debug_assert!(!edge_ref.is_none());
 
-                let edge_label_begin = match edge_ref.unpack() {
-                    NodeRef::Leaf(pos) => pos,
-                    NodeRef::Inner(idx) => self.nodes[idx].begin.unpack()
-                };
-                let cur_label_pos = edge_label_begin + new_end - cur.pos;
-                let cur_label_ch = self.payload[cur_label_pos];
-
-                // Do we match the next character of the edge label or not?
-                if new_ch == cur_label_ch {
-                    // We do; nothing to do for now!
-                    break;
-                } else {
-                    // We don't, so we need to split this edge
-                    let mut n = Node::new(edge_label_begin, cur_label_pos, 1);
-                    // Transfer over the existing node as first child
-                    n.child[cur_label_ch as usize] = match edge_ref.unpack() {
-                        NodeRef::Leaf(_) => PackedRef::from_leaf(cur_label_pos),
-                        NodeRef::Inner(idx) => {
-                            // Update the inner node to shorten its edge label
-                            self.nodes[idx].begin = PackedPos::from(cur_label_pos);
-                            PackedRef::from_inner(idx)
-                        }
-                    };
-                    // Insert the new node and remember its index
-                    let new_node_idx = self.nodes.len();
-                    self.nodes.push(n);
-                    // Link in the newly create node right below the active node
-                    self.nodes[cur.node].child[edge_select_ch] = PackedRef::from_inner(new_node_idx);
-                    // Return the index of the newly created node
-                    new_node_idx
-                }
+            let edge_label_begin = match edge_ref.unpack() {
+                NodeRef::Leaf(pos) => pos,
+                NodeRef::Inner(idx) => nodes[idx].begin.unpack()
             };
+            let cur_label_pos = edge_label_begin + new_end - cur.pos;
+            let cur_label_ch = payload[cur_label_pos];
+
+            // Do we match the next character of the edge label or not?
+            if new_ch == cur_label_ch {
+                // We do; nothing to do for now! Same suffix-link caveat as
+                // the other early-out above.
+                nodes[prev_insert_idx].suffix = PackedRef::from_inner(cur.node);
+                break;
+            } else {
+                // We don't, so we need to split this edge
+                let mut n = Node::new(edge_label_begin, cur_label_pos, 1);
+                // Transfer over the existing node as first child
+                let transferred_ref = match edge_ref.unpack() {
+                    NodeRef::Leaf(_) => PackedRef::from_leaf(cur_label_pos),
+                    NodeRef::Inner(idx) => {
+                        // Update the inner node to shorten its edge label
+                        nodes[idx].begin = PackedPos::from(cur_label_pos);
+                        PackedRef::from_inner(idx)
+                    }
+                };
+                n.set_child::<A>(cur_label_ch, transferred_ref);
+                // Insert the new node and remember its index
+                let new_node_idx = nodes.len();
+                nodes.push(n);
+                // Link in the newly create node right below the active node
+                nodes[cur.node].set_child::<A>(edge_select_ch, PackedRef::from_inner(new_node_idx));
+                // Return the index of the newly created node
+                new_node_idx
+            }
+        };
 
-            // Update the suffix links
-            self.nodes[prev_insert_idx].suffix = PackedRef::from_inner(insert_node_idx);
-            prev_insert_idx = insert_node_idx;
+        // Update the suffix links
+        nodes[prev_insert_idx].suffix = PackedRef::from_inner(insert_node_idx);
+        prev_insert_idx = insert_node_idx;
 
-            // Add the new leaf
-            debug_assert!(self.nodes[insert_node_idx].child[new_ch as usize].is_none());
-            self.nodes[insert_node_idx].child[new_ch as usize] = PackedRef::from_leaf(new_end);
+        // Add the new leaf
+        debug_assert!(nodes[insert_node_idx].get_child::<A>(new_ch).is_none());
+        nodes[insert_node_idx].set_child::<A>(new_ch, PackedRef::from_leaf(new_end));
 
-            // Continue on to the next suffix
-            if let NodeRef::Inner(idx) = self.nodes[cur.node].suffix.unpack() {
-                cur.node = idx;
-            } else {
-                panic!("Suffix links must be to inner nodes!");
-            }
+        // Continue on to the next suffix
+        if let NodeRef::Inner(idx) = nodes[cur.node].suffix.unpack() {
+            cur.node = idx;
+        } else {
+            panic!("Suffix links must be to inner nodes!");
         }
+    }
+
+    cur
+}
 
-        cur
+// Minimum-width varint used by SuffixTree::save/load: a tag byte giving the
+// number of significant little-endian bytes (0..=4), followed by exactly
+// that many bytes. Values are zero-extended on read.
+fn write_varint(w: &mut impl Write, val: u32) -> io::Result<()> {
+    let bytes = val.to_le_bytes();
+    let mut n = 4;
+    while n > 0 && bytes[n - 1] == 0 {
+        n -= 1;
+    }
+    w.write_all(&[n as u8])?;
+    w.write_all(&bytes[..n])
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u32> {
+    let mut tag = [0u8; 1];
+    r.read_exact(&mut tag)?;
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes[..tag[0] as usize])?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Cheap integrity check for the externally-kept payload (FNV-1a).
+fn payload_checksum(payload: &[u8]) -> u32 {
+    let mut h: u32 = 0x811c_9dc5;
+    for &b in payload {
+        h ^= b as u32;
+        h = h.wrapping_mul(0x0100_0193);
+    }
+    h
+}
+
+struct SuffixTree<'a, const SIGMA: usize = 256, A: Alphabet<SIGMA> = Identity> {
+    payload: Cow<'a, [u8]>,
+    nodes: Vec<Node<SIGMA>>,
+    // Exclusive end offset (in payload) of each concatenated document, in
+    // order. A tree over a single string has one entry equal to payload.len().
+    doc_bounds: Vec<usize>,
+    _alphabet: PhantomData<A>,
+}
+
+impl<'a, const SIGMA: usize, A: Alphabet<SIGMA>> SuffixTree<'a, SIGMA, A> {
+    fn update(&mut self, cur_in: Cursor, new_end: usize) -> Cursor {
+        advance::<SIGMA, A>(&mut self.nodes, &self.payload, cur_in, new_end)
     }
 
     fn print_rec(&self, node: NodeRef, indent: usize, cur_end: usize) {
@@ -226,10 +540,8 @@ impl<'a> SuffixTree<'a> {
                         str::from_utf8(&self.payload[n.begin.unpack()..n.end.unpack()]).unwrap(),
                         idx, suffix_ind);
                 }
-                for r in n.child.iter() {
-                    if !r.is_none() {
-                        self.print_rec(r.unpack(), indent + 1, cur_end);
-                    }
+                for (_, r) in n.child.iter() {
+                    self.print_rec(r.unpack(), indent + 1, cur_end);
                 }
             },
             NodeRef::Leaf(pos) => {
@@ -242,8 +554,33 @@ impl<'a> SuffixTree<'a> {
         self.print_rec(NodeRef::Inner(1), 0, self.payload.len());
     }
 
-    fn from(payload: &'a [u8]) -> SuffixTree<'a> {
-        let mut st = SuffixTree { payload: payload, nodes: Vec::new() };
+    fn from(payload: &'a [u8]) -> SuffixTree<'a, SIGMA, A> {
+        let len = payload.len();
+        Self::build(Cow::Borrowed(payload), vec![len])
+    }
+
+    // Generalized suffix tree over a whole collection of documents. Each
+    // document is given its own terminator byte (counting down from 0xff)
+    // so suffixes from different documents never get folded into the same
+    // leaf, mirroring how a single `from` call relies on its caller-supplied
+    // terminator (e.g. the "$" in "bananas$").
+    fn from_documents(docs: &[&[u8]]) -> SuffixTree<'static, SIGMA, A> {
+        assert!(!docs.is_empty(), "need at least one document");
+        assert!(docs.len() <= 0x100, "at most 256 documents fit in a single terminator byte");
+
+        let mut payload = Vec::new();
+        let mut doc_bounds = Vec::with_capacity(docs.len());
+        for (i, doc) in docs.iter().enumerate() {
+            payload.extend_from_slice(doc);
+            payload.push((0xff - i) as u8);
+            doc_bounds.push(payload.len());
+        }
+
+        SuffixTree::build(Cow::Owned(payload), doc_bounds)
+    }
+
+    fn build<'b>(payload: Cow<'b, [u8]>, doc_bounds: Vec<usize>) -> SuffixTree<'b, SIGMA, A> {
+        let mut st = SuffixTree { payload, nodes: Vec::new(), doc_bounds, _alphabet: PhantomData };
 
         // Add the two sentinel nodes
         // Top is node 0. All child links point to the root.
@@ -253,14 +590,419 @@ impl<'a> SuffixTree<'a> {
         st.nodes.push(Node::new(0, 1, 0));
 
         // Update the suffix tree, adding the characters one by one
-        (0..payload.len()).fold(Cursor { node: 1, pos: 0 }, |curs, pos| st.update(curs, pos));
+        let len = st.payload.len();
+        (0..len).fold(Cursor { node: 1, pos: 0 }, |curs, pos| st.update(curs, pos));
 
         st
     }
+
+    // Which document a payload position belongs to.
+    fn doc_of(&self, pos: usize) -> usize {
+        self.doc_bounds.partition_point(|&end| end <= pos)
+    }
+
+    // All distinct documents containing `pat`, in ascending order.
+    fn documents_containing(&self, pat: &[u8]) -> Vec<usize> {
+        let mut docs: Vec<usize> = self.find_all(pat).iter().map(|&pos| self.doc_of(pos)).collect();
+        docs.sort_unstable();
+        docs.dedup();
+        docs
+    }
+
+    // Post-order pass accumulating, for each internal node, the set of
+    // distinct document ids among the leaves in its subtree; updates `best`
+    // with the deepest node seen so far whose subtree spans >= k documents.
+    fn doc_colors(&self, idx: usize, depth: usize, k: usize, best: &mut (usize, usize)) -> HashSet<usize> {
+        let mut colors = HashSet::new();
+        for (_, r) in self.nodes[idx].child.iter() {
+            match r.unpack() {
+                NodeRef::Leaf(pos) => {
+                    colors.insert(self.doc_of(pos - depth));
+                }
+                NodeRef::Inner(cidx) => {
+                    colors.extend(self.doc_colors(cidx, depth + self.nodes[cidx].label_len(), k, best));
+                }
+            }
+        }
+        if depth > best.1 && colors.len() >= k {
+            *best = (idx, depth);
+        }
+        colors
+    }
+
+    // Longest substring appearing in at least `k` of the collection's documents.
+    fn longest_common_substring(&self, k: usize) -> &[u8] {
+        let mut best = (1, 0); // (node idx, string-depth); root/depth 0 means "none found"
+        self.doc_colors(1, 0, k, &mut best);
+        let (idx, depth) = best;
+        let end = self.nodes[idx].end.unpack();
+        &self.payload[end - depth..end]
+    }
+
+    // Persists the node table so it doesn't need to be rebuilt next run. The
+    // payload itself is kept external (only its length and a checksum are
+    // written) since nodes only ever reference it by position.
+    fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        write_varint(w, self.payload.len() as u32)?;
+        write_varint(w, payload_checksum(&self.payload))?;
+
+        write_varint(w, self.doc_bounds.len() as u32)?;
+        for &bound in &self.doc_bounds {
+            write_varint(w, bound as u32)?;
+        }
+
+        write_varint(w, self.nodes.len() as u32)?;
+        for (i, n) in self.nodes.iter().enumerate() {
+            write_varint(w, n.begin.0)?;
+            write_varint(w, n.end.0)?;
+            write_varint(w, n.suffix.0)?;
+
+            // Node 0 (Top) is always `ChildLayout::filled`, whose `iter()`
+            // keys are in `Alphabet::index` space rather than raw bytes (see
+            // `ChildIter`'s doc comment); round-tripping those through
+            // `set_child` on load would re-apply the mapping and scramble a
+            // non-bijective alphabet's children. Top's structure is fixed by
+            // construction (see `SuffixTree::build`), so skip it here and
+            // rebuild it directly on load instead.
+            if i == 0 {
+                write_varint(w, 0)?;
+                continue;
+            }
+
+            let children: Vec<(u8, PackedRef)> = n.child.iter().collect();
+            write_varint(w, children.len() as u32)?;
+            for (ch, r) in children {
+                write_varint(w, ch as u32)?;
+                write_varint(w, r.0)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Counterpart to `save`. Since the payload wasn't serialized, the caller
+    // passes back the same bytes `save` was called with; loading fails if
+    // they don't match what was persisted.
+    fn load(r: &mut impl Read, payload: &'a [u8]) -> io::Result<SuffixTree<'a, SIGMA, A>> {
+        let len = read_varint(r)? as usize;
+        let checksum = read_varint(r)?;
+        if len != payload.len() || checksum != payload_checksum(payload) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "payload does not match the one this tree was saved with"));
+        }
+
+        let doc_count = read_varint(r)? as usize;
+        let mut doc_bounds = Vec::with_capacity(doc_count);
+        for _ in 0..doc_count {
+            doc_bounds.push(read_varint(r)? as usize);
+        }
+
+        let node_count = read_varint(r)? as usize;
+        let mut nodes = Vec::with_capacity(node_count);
+        for i in 0..node_count {
+            let mut node = Node {
+                begin: PackedPos(read_varint(r)?),
+                end: PackedPos(read_varint(r)?),
+                suffix: PackedRef(read_varint(r)?),
+                child: ChildLayout::empty(),
+            };
+
+            let child_count = read_varint(r)?;
+            for _ in 0..child_count {
+                let ch = read_varint(r)? as u8;
+                let packed = read_varint(r)?;
+                node.set_child::<A>(ch, PackedRef(packed));
+            }
+            // Top's children were skipped by `save` (see there); rebuild the
+            // fixed structure directly instead of replaying it byte-by-byte.
+            if i == 0 {
+                node.child = ChildLayout::filled(PackedRef::from_inner(1));
+            }
+            nodes.push(node);
+        }
+
+        Ok(SuffixTree { payload: Cow::Borrowed(payload), nodes, doc_bounds, _alphabet: PhantomData })
+    }
+
+    // Walks from the root, consuming `pat` against edge labels. Returns where
+    // the walk ends, or None on a mismatch.
+    fn locate(&self, pat: &[u8]) -> Option<MatchPoint> {
+        if pat.is_empty() {
+            return Some(MatchPoint::Subtree(1, 0));
+        }
+
+        let mut node_idx = 1; // root
+        let mut i = 0;
+        loop {
+            let child = self.nodes[node_idx].get_child::<A>(pat[i]);
+            if child.is_none() {
+                return None;
+            }
+            match child.unpack() {
+                NodeRef::Leaf(pos) => {
+                    let remaining = pat.len() - i;
+                    let label = &self.payload[pos..];
+                    return if remaining <= label.len() && label[..remaining] == pat[i..] {
+                        Some(MatchPoint::Leaf(pos - i))
+                    } else {
+                        None
+                    };
+                }
+                NodeRef::Inner(idx) => {
+                    let n = &self.nodes[idx];
+                    let label = &self.payload[n.begin.unpack()..n.end.unpack()];
+                    let remaining = pat.len() - i;
+                    if remaining <= label.len() {
+                        return if label[..remaining] == pat[i..] {
+                            Some(MatchPoint::Subtree(idx, i + label.len()))
+                        } else {
+                            None
+                        };
+                    }
+                    if label != &pat[i..i + label.len()] {
+                        return None;
+                    }
+                    i += label.len();
+                    node_idx = idx;
+                }
+            }
+        }
+    }
+
+    // Gathers the suffix start positions of every leaf in the subtree rooted
+    // at `idx`, where `depth` is the string-depth of `idx` (i.e. the length
+    // of the path label from the root through idx's own incoming edge).
+    fn collect_leaves(&self, idx: usize, depth: usize, out: &mut Vec<usize>) {
+        for (_, r) in self.nodes[idx].child.iter() {
+            match r.unpack() {
+                NodeRef::Leaf(pos) => out.push(pos - depth),
+                NodeRef::Inner(cidx) => {
+                    self.collect_leaves(cidx, depth + self.nodes[cidx].label_len(), out);
+                }
+            }
+        }
+    }
+
+    fn contains(&self, pat: &[u8]) -> bool {
+        self.locate(pat).is_some()
+    }
+
+    fn find_all(&self, pat: &[u8]) -> Vec<usize> {
+        let mut out = match self.locate(pat) {
+            None => return Vec::new(),
+            Some(MatchPoint::Leaf(start)) => vec![start],
+            Some(MatchPoint::Subtree(idx, depth)) => {
+                let mut out = Vec::new();
+                self.collect_leaves(idx, depth, &mut out);
+                out
+            }
+        };
+        out.sort_unstable();
+        out
+    }
+
+    fn count(&self, pat: &[u8]) -> usize {
+        self.find_all(pat).len()
+    }
+
+    // Deepest internal node with at least two children is the locus of the
+    // longest substring that repeats somewhere in the payload.
+    fn find_longest_repeated(&self, idx: usize, depth: usize, best: &mut (usize, usize)) {
+        if depth > best.1 && self.nodes[idx].child.iter().count() >= 2 {
+            *best = (idx, depth);
+        }
+        for (_, r) in self.nodes[idx].child.iter() {
+            if let NodeRef::Inner(cidx) = r.unpack() {
+                self.find_longest_repeated(cidx, depth + self.nodes[cidx].label_len(), best);
+            }
+        }
+    }
+
+    fn longest_repeated_substring(&self) -> &[u8] {
+        let mut best = (1, 0); // (node idx, string-depth); root/depth 0 means "none found"
+        self.find_longest_repeated(1, 0, &mut best);
+        let (idx, depth) = best;
+        let end = self.nodes[idx].end.unpack();
+        &self.payload[end - depth..end]
+    }
+}
+
+// Builds a `SuffixTree` incrementally from bytes as they arrive, rather than
+// requiring the whole payload up front. This just exposes the online nature
+// of Ukkonen's algorithm directly: `push`/`extend` drive `advance` one
+// position at a time, carrying the active `Cursor` across calls the same
+// way `SuffixTree::build`'s fold does internally.
+struct SuffixTreeBuilder<const SIGMA: usize = 256, A: Alphabet<SIGMA> = Identity> {
+    payload: Vec<u8>,
+    nodes: Vec<Node<SIGMA>>,
+    cursor: Cursor,
+    _alphabet: PhantomData<A>,
+}
+
+impl<const SIGMA: usize, A: Alphabet<SIGMA>> SuffixTreeBuilder<SIGMA, A> {
+    fn new() -> Self {
+        // Same two sentinel nodes as `SuffixTree::build`.
+        let nodes = vec![Node::new_special(0, 0, 0, 1), Node::new(0, 1, 0)];
+        SuffixTreeBuilder { payload: Vec::new(), nodes, cursor: Cursor { node: 1, pos: 0 }, _alphabet: PhantomData }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.payload.push(byte);
+        let new_end = self.payload.len() - 1;
+        self.cursor = advance::<SIGMA, A>(&mut self.nodes, &self.payload, self.cursor, new_end);
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.push(b);
+        }
+    }
+
+    // Appends 0xff (the same sentinel `from_documents` uses for its first
+    // document) as the terminator and returns the finished tree. The
+    // terminator is unique only if no pushed byte was 0xff; `push` does not
+    // check this, just as `from` relies on its caller's terminator.
+    fn finish(mut self) -> SuffixTree<'static, SIGMA, A> {
+        self.push(0xff);
+        let len = self.payload.len();
+        SuffixTree {
+            payload: Cow::Owned(self.payload),
+            nodes: self.nodes,
+            doc_bounds: vec![len],
+            _alphabet: PhantomData,
+        }
+    }
+}
+
+impl<const SIGMA: usize, A: Alphabet<SIGMA>> Default for SuffixTreeBuilder<SIGMA, A> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 fn main() {
     let payload = "bananas$".as_bytes();
-    let st = SuffixTree::from(payload);
+    let st: SuffixTree = SuffixTree::from(payload);
     st.print();
+
+    println!("contains(\"ana\") = {}", st.contains(b"ana"));
+    println!("find_all(\"ana\") = {:?}", st.find_all(b"ana"));
+    println!("count(\"a\") = {}", st.count(b"a"));
+    println!("longest_repeated_substring = {:?}",
+        str::from_utf8(st.longest_repeated_substring()).unwrap());
+
+    let gst: SuffixTree = SuffixTree::from_documents(&["bananas".as_bytes(), "ananas".as_bytes(), "banana".as_bytes()]);
+    println!("documents_containing(\"ana\") = {:?}", gst.documents_containing(b"ana"));
+    println!("longest_common_substring(k=3) = {:?}",
+        str::from_utf8(gst.longest_common_substring(3)).unwrap());
+
+    let mut buf = Vec::new();
+    st.save(&mut buf).unwrap();
+    let reloaded: SuffixTree = SuffixTree::load(&mut &buf[..], payload).unwrap();
+    println!("reloaded find_all(\"ana\") = {:?}", reloaded.find_all(b"ana"));
+
+    let dna = "ACGTACGT$".as_bytes();
+    let dna_st: SuffixTree<5, DnaAlphabet> = SuffixTree::from(dna);
+    println!("dna contains(\"CGTA\") = {}", dna_st.contains(b"CGTA"));
+    println!("dna count(\"A\") = {}", dna_st.count(b"A"));
+
+    let mut builder: SuffixTreeBuilder = SuffixTreeBuilder::new();
+    for chunk in ["bana", "nas"] {
+        builder.extend(chunk.as_bytes());
+    }
+    let streamed = builder.finish();
+    println!("streamed find_all(\"ana\") = {:?}", streamed.find_all(b"ana"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Regression coverage for a dropped-suffix bug in `advance`: a split
+    // created earlier in a phase left its suffix link pointing at the
+    // placeholder root instead of the next extension's active point
+    // whenever that extension broke out via Rule 3 instead of inserting.
+    // That silently skipped one suffix's leaf in repeated-character inputs
+    // even with a unique terminator.
+    #[test]
+    fn repeated_characters_are_all_findable() {
+        let st: SuffixTree = SuffixTree::from(b"aababaaa$".as_slice());
+        assert_eq!(st.find_all(b"a"), vec![0, 1, 3, 5, 6, 7]);
+        assert_eq!(st.count(b"a"), 6);
+    }
+
+    #[test]
+    fn longest_repeated_substring_finds_the_actual_longest() {
+        let st: SuffixTree = SuffixTree::from(b"aabaaaababb$".as_slice());
+        assert_eq!(st.longest_repeated_substring().len(), 4);
+    }
+
+    #[test]
+    fn every_suffix_of_a_repetitive_string_is_explicit() {
+        let payload = b"aabaaa$";
+        let st: SuffixTree = SuffixTree::from(payload.as_slice());
+        for i in 0..payload.len() {
+            assert!(st.contains(&payload[i..]), "suffix {i} not found");
+        }
+    }
+
+    // documents_containing and longest_common_substring inherit the same
+    // dropped-suffix defect through find_all/doc_colors: a short document
+    // whose only occurrence of a pattern is its own trailing suffix was
+    // silently missing from the result.
+    #[test]
+    fn documents_containing_finds_short_trailing_matches() {
+        let gst: SuffixTree = SuffixTree::from_documents(&[b"bb", b"b", b"ababb", b"aba"]);
+        assert_eq!(gst.documents_containing(b"b"), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn longest_common_substring_across_repetitive_documents() {
+        let gst: SuffixTree =
+            SuffixTree::from_documents(&["bananas".as_bytes(), "ananas".as_bytes(), "banana".as_bytes()]);
+        assert_eq!(gst.longest_common_substring(3), b"anana");
+    }
+
+    // SuffixTreeBuilder drives the same `advance` as the batch builder, one
+    // byte at a time; it should reach the same answers, including on the
+    // repeated-character inputs that regressed construction above.
+    #[test]
+    fn streaming_builder_matches_batch_construction() {
+        let mut builder: SuffixTreeBuilder = SuffixTreeBuilder::new();
+        builder.extend(b"aababaaa");
+        let streamed = builder.finish();
+        assert_eq!(streamed.find_all(b"a"), vec![0, 1, 3, 5, 6, 7]);
+        assert_eq!(streamed.count(b"a"), 6);
+    }
+
+    #[test]
+    fn streaming_builder_chunk_boundaries_dont_matter() {
+        let mut builder: SuffixTreeBuilder = SuffixTreeBuilder::new();
+        for chunk in [b"bana".as_slice(), b"nas".as_slice()] {
+            builder.extend(chunk);
+        }
+        let streamed = builder.finish();
+        let batch: SuffixTree = SuffixTree::from(b"bananas$".as_slice());
+        assert_eq!(streamed.find_all(b"ana"), batch.find_all(b"ana"));
+    }
+
+    // Node 0 (Top) is a NodeFull keyed in index space; round-tripping it
+    // through save/load used to replay those keys as raw bytes on a
+    // non-bijective alphabet, scrambling its children. Top is never queried
+    // directly, but the round trip itself must not corrupt it (or anything
+    // else) for DNA-alphabet trees.
+    #[test]
+    fn dna_tree_round_trips_through_save_load() {
+        let payload = b"ACGTACGT$";
+        let st: SuffixTree<5, DnaAlphabet> = SuffixTree::from(payload.as_slice());
+
+        let mut buf = Vec::new();
+        st.save(&mut buf).unwrap();
+        let reloaded: SuffixTree<5, DnaAlphabet> = SuffixTree::load(&mut &buf[..], payload).unwrap();
+
+        assert_eq!(reloaded.find_all(b"CGTA"), st.find_all(b"CGTA"));
+        assert_eq!(reloaded.count(b"A"), st.count(b"A"));
+        for i in 0..payload.len() {
+            assert!(reloaded.contains(&payload[i..]), "suffix {i} not found after reload");
+        }
+    }
 }